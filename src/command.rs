@@ -2,28 +2,192 @@ use dind;
 use errors::FlokiError;
 use quicli::prelude::*;
 use std::env;
+use std::fs;
 use std::path;
+use std::path::PathBuf;
+use std::process;
 use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::Duration;
+
+/// Container engine, from a config value or `FLOKI_CONTAINER_ENGINE`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Engine {
+    Docker,
+    Podman,
+    Path(String),
+}
+
+impl Engine {
+    pub fn from_config_or_env(config: Option<&str>) -> Self {
+        match config.map(String::from).or_else(|| env::var("FLOKI_CONTAINER_ENGINE").ok()) {
+            Some(ref s) if s == "docker" => Engine::Docker,
+            Some(ref s) if s == "podman" => Engine::Podman,
+            Some(s) => Engine::Path(s),
+            None => Engine::Docker,
+        }
+    }
+
+    fn binary(&self) -> &str {
+        match self {
+            Engine::Docker => "docker",
+            Engine::Podman => "podman",
+            Engine::Path(p) => p,
+        }
+    }
+
+    fn is_podman(&self) -> bool {
+        match self {
+            Engine::Podman => true,
+            Engine::Path(p) => p.ends_with("podman"),
+            Engine::Docker => false,
+        }
+    }
+}
+
+/// Which mechanism `DockerCommandBuilder::run` uses to launch the container.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunBackend {
+    Cli,
+    Api,
+}
+
+/// Mirrors the `--pull=` flag docker/podman understand directly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PullPolicy {
+    Always,
+    Missing,
+    Never,
+}
+
+impl PullPolicy {
+    fn as_flag_value(&self) -> &'static str {
+        match self {
+            PullPolicy::Always => "always",
+            PullPolicy::Missing => "missing",
+            PullPolicy::Never => "never",
+        }
+    }
+}
+
+impl Default for PullPolicy {
+    fn default() -> Self {
+        PullPolicy::Missing
+    }
+}
+
+/// floki's bundled hardened seccomp profile.
+const SECCOMP_PROFILE_JSON: &str = include_str!("../resources/floki-seccomp.json");
+
+#[derive(Debug, Clone, Default)]
+pub struct SecurityOptions {
+    pub seccomp: bool,
+    pub cap_add: Vec<String>,
+    pub cap_drop: Vec<String>,
+    pub read_only: bool,
+}
+
+/// Cleans up the temporary seccomp profile file on drop.
+#[derive(Debug)]
+struct SeccompProfileGuard {
+    path: PathBuf,
+}
+
+impl SeccompProfileGuard {
+    fn write() -> Result<Self> {
+        let mut path = env::temp_dir();
+        path.push(format!("floki-seccomp-{}.json", process::id()));
+        fs::write(&path, SECCOMP_PROFILE_JSON).map_err(|e| FlokiError::FailedToWriteSeccompProfile { error: e })?;
+        Ok(SeccompProfileGuard { path })
+    }
+}
+
+impl Drop for SeccompProfileGuard {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Failed to remove temporary seccomp profile {:?}: {}", self.path, e);
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct DockerCommandBuilder {
     volumes: Vec<(String, String)>,
+    // Socket bind mounts (ssh-agent/tmux/gpg-agent forwarding): these are
+    // live unix sockets, not files `tar` can meaningfully archive into a
+    // remote data volume, so they're always bind-mounted directly.
+    socket_mounts: Vec<(String, String)>,
     environment: Vec<(String, String)>,
     shell: String,
     switches: Vec<String>,
     image: String,
+    engine: Engine,
+    backend: RunBackend,
+    ports: Vec<(u16, u16)>,
+    pull_policy: PullPolicy,
+    /// Some older engines/versions don't understand `--pull=`; set this
+    /// to `false` to fall back to an explicit `pull` step before `run`
+    /// (taken only for `PullPolicy::Always`; see `pull_image_fallback`).
+    use_pull_flag: bool,
+    security: SecurityOptions,
 }
 
 impl DockerCommandBuilder {
     pub fn run(&self, subshell_command: String) -> Result<ExitStatus> {
+        match self.backend {
+            RunBackend::Cli => self.run_via_cli(subshell_command),
+            #[cfg(feature = "docker-api")]
+            RunBackend::Api => api::run_via_api(self, subshell_command),
+            #[cfg(not(feature = "docker-api"))]
+            RunBackend::Api => Err(FlokiError::UnsupportedEngineFeature {
+                engine: format!("{:?}", self.engine),
+                feature: "docker-api (not compiled in)".into(),
+            })?,
+        }
+    }
+
+    fn run_via_cli(&self, subshell_command: String) -> Result<ExitStatus> {
         debug!(
-            "Spawning docker command with configuration: {:?} args: {}",
-            self, &subshell_command
+            "Spawning {:?} command with configuration: {:?} args: {}",
+            self.engine, self, &subshell_command
         );
-        let mut command = Command::new("docker")
+
+        if !self.use_pull_flag {
+            self.pull_image_fallback()?;
+        }
+
+        // Held until after `wait()` so the seccomp profile file stays on
+        // disk for the lifetime of the container.
+        let _seccomp_guard = if self.security.seccomp {
+            Some(SeccompProfileGuard::write()?)
+        } else {
+            None
+        };
+
+        let container_name = format!("floki-{}", process::id());
+
+        // On a remote daemon a bind-mount would refer to a path on that
+        // remote machine, not this one, so copy the workspace into a
+        // named data volume instead and stream it back out afterwards.
+        let remote_volumes = if should_use_remote_volumes() {
+            Some(self.prepare_remote_volumes(&container_name)?)
+        } else {
+            None
+        };
+        let mut volume_switches = match &remote_volumes {
+            Some(prepared) => Self::remote_volume_switches(prepared),
+            None => self.build_volume_switches(),
+        };
+        volume_switches.extend(self.build_socket_mount_switches());
+
+        let mut command = Command::new(self.engine.binary())
             .args(&["run", "--rm", "-it"])
-            .args(&self.build_volume_switches())
+            .args(&["--name", &container_name])
+            .args(&self.build_pull_switch())
+            .args(&volume_switches)
             .args(&self.build_environment_switches())
+            .args(&self.build_port_switches())
+            .args(&self.build_security_switches(_seccomp_guard.as_ref()))
             .args(&self.build_docker_switches())
             .arg(&self.image)
             .arg(&self.shell)
@@ -35,28 +199,192 @@ impl DockerCommandBuilder {
             .spawn()
             .map_err(|e| FlokiError::FailedToLaunchDocker { error: e })?;
 
+        if !self.ports.is_empty() {
+            self.report_port_mappings(&container_name);
+        }
+
         let exit_status = command
             .wait()
             .map_err(|e| FlokiError::FailedToCompleteDockerCommand { error: e })?;
 
+        if let Some(prepared) = &remote_volumes {
+            for (host_dir, _container_dir, guard) in prepared.iter() {
+                guard.copy_back(host_dir)?;
+            }
+        }
+
         Ok(exit_status)
     }
 
+    fn prepare_remote_volumes(
+        &self,
+        container_name: &str,
+    ) -> Result<Vec<(String, String, DataVolumeGuard)>> {
+        let mut prepared = Vec::new();
+        for (index, (host_dir, container_dir)) in self.volumes.iter().enumerate() {
+            let volume_name = format!("{}-vol{}", container_name, index);
+            let guard = DataVolumeGuard::create(&self.engine, &volume_name)?;
+            guard.populate(host_dir)?;
+            prepared.push((host_dir.clone(), container_dir.clone(), guard));
+        }
+        Ok(prepared)
+    }
+
+    fn remote_volume_switches(prepared: &[(String, String, DataVolumeGuard)]) -> Vec<String> {
+        let mut switches = Vec::new();
+        for (_host_dir, container_dir, guard) in prepared.iter() {
+            switches.push("-v".into());
+            switches.push(format!("{}:{}", guard.name, container_dir));
+        }
+        switches
+    }
+
+    fn build_security_switches(&self, seccomp_guard: Option<&SeccompProfileGuard>) -> Vec<String> {
+        let mut switches = Vec::new();
+        if let Some(guard) = seccomp_guard {
+            switches.push("--security-opt".into());
+            switches.push(format!("seccomp={}", guard.path.display()));
+        }
+        for cap in self.security.cap_add.iter() {
+            switches.push("--cap-add".into());
+            switches.push(cap.clone());
+        }
+        for cap in self.security.cap_drop.iter() {
+            switches.push("--cap-drop".into());
+            switches.push(cap.clone());
+        }
+        if self.security.read_only {
+            switches.push("--read-only".into());
+        }
+        switches
+    }
+
+    fn build_pull_switch(&self) -> Vec<String> {
+        if self.use_pull_flag {
+            vec![format!("--pull={}", self.pull_policy.as_flag_value())]
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Fallback for engines without `--pull=`.
+    fn pull_image_fallback(&self) -> Result<()> {
+        match self.pull_policy {
+            PullPolicy::Always => {
+                debug!("Pulling {} (pull policy: always)", self.image);
+                let status = Command::new(self.engine.binary())
+                    .args(&["pull", &self.image])
+                    .status()
+                    .map_err(|e| FlokiError::FailedToLaunchDocker { error: e })?;
+                if !status.success() {
+                    Err(FlokiError::FailedToPullImage {
+                        image: self.image.clone(),
+                    })?
+                }
+                Ok(())
+            }
+            PullPolicy::Missing => Ok(()),
+            PullPolicy::Never => {
+                warn!(
+                    "pull policy is 'never' but this engine lacks --pull support; \
+                     an absent image may still be pulled automatically"
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Surfaces the host port actually bound when `0` was requested.
+    /// `docker port` races container creation immediately after `spawn()`
+    /// (worse under `--pull=always`), so retry for a few seconds before
+    /// giving up.
+    fn report_port_mappings(&self, container_name: &str) {
+        const ATTEMPTS: u32 = 20;
+        const RETRY_DELAY: Duration = Duration::from_millis(250);
+
+        for attempt in 0..ATTEMPTS {
+            match Command::new(self.engine.binary())
+                .args(&["port", container_name])
+                .output()
+            {
+                Ok(output) if output.status.success() => {
+                    for line in String::from_utf8_lossy(&output.stdout).lines() {
+                        info!("Published port: {}", line);
+                    }
+                    return;
+                }
+                Ok(output) => {
+                    if attempt + 1 == ATTEMPTS {
+                        warn!(
+                            "Could not determine published ports: {}",
+                            String::from_utf8_lossy(&output.stderr)
+                        );
+                    }
+                }
+                Err(e) => {
+                    if attempt + 1 == ATTEMPTS {
+                        warn!("Could not determine published ports: {}", e);
+                    }
+                }
+            }
+            thread::sleep(RETRY_DELAY);
+        }
+    }
+
     pub fn new(image: &str, shell: &str) -> Self {
         DockerCommandBuilder {
             volumes: Vec::new(),
+            socket_mounts: Vec::new(),
             environment: Vec::new(),
             shell: shell.into(),
             switches: Vec::new(),
             image: image.into(),
+            engine: Engine::from_config_or_env(None),
+            backend: RunBackend::Cli,
+            ports: Vec::new(),
+            pull_policy: PullPolicy::default(),
+            use_pull_flag: true,
+            security: SecurityOptions::default(),
         }
     }
 
+    pub fn with_engine(mut self, engine: Engine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    pub fn with_security_options(mut self, security: SecurityOptions) -> Self {
+        self.security = security;
+        self
+    }
+
+    pub fn with_pull_policy(mut self, pull_policy: PullPolicy) -> Self {
+        self.pull_policy = pull_policy;
+        self
+    }
+
+    pub fn with_pull_flag_support(mut self, supported: bool) -> Self {
+        self.use_pull_flag = supported;
+        self
+    }
+
+    pub fn with_backend(mut self, backend: RunBackend) -> Self {
+        self.backend = backend;
+        self
+    }
+
     pub fn add_volume(mut self, spec: &(String, String)) -> Self {
         self.volumes.push(spec.clone());
         self
     }
 
+    /// Always bind-mounted, even when `should_use_remote_volumes()` is
+    /// true: see the comment on the `socket_mounts` field.
+    pub fn add_socket_mount(mut self, spec: &(String, String)) -> Self {
+        self.socket_mounts.push(spec.clone());
+        self
+    }
+
     pub fn add_environment(mut self, spec: &(String, String)) -> Self {
         self.environment.push(spec.clone());
         self
@@ -67,6 +395,21 @@ impl DockerCommandBuilder {
         self
     }
 
+    /// Publish `spec.0` on the host to `spec.1` in the container.
+    pub fn add_port(mut self, spec: (u16, u16)) -> Self {
+        self.ports.push(spec);
+        self
+    }
+
+    fn build_port_switches(&self) -> Vec<String> {
+        let mut switches = Vec::new();
+        for (host, container) in self.ports.iter() {
+            switches.push("-p".into());
+            switches.push(format!("{}:{}", host, container));
+        }
+        switches
+    }
+
     fn build_volume_switches(&self) -> Vec<String> {
         let mut switches = Vec::new();
         for (s, d) in self.volumes.iter() {
@@ -76,6 +419,15 @@ impl DockerCommandBuilder {
         switches
     }
 
+    fn build_socket_mount_switches(&self) -> Vec<String> {
+        let mut switches = Vec::new();
+        for (s, d) in self.socket_mounts.iter() {
+            switches.push("-v".into());
+            switches.push(format!("{}:{}", s, d));
+        }
+        switches
+    }
+
     fn build_environment_switches(&self) -> Vec<String> {
         let mut switches = Vec::new();
         for (var, bind) in self.environment.iter() {
@@ -97,19 +449,52 @@ impl DockerCommandBuilder {
     }
 }
 
+/// Shared primitive behind ssh-agent/tmux/gpg-agent forwarding.
+fn forward_host_socket(
+    command: DockerCommandBuilder,
+    socket_path: &str,
+    container_dir: &str,
+    env_var: Option<&str>,
+    what: &str,
+) -> Result<DockerCommandBuilder> {
+    let socket = path::Path::new(socket_path);
+    match (
+        socket.parent().and_then(|p| p.to_str()),
+        socket.file_name().and_then(|f| f.to_str()),
+    ) {
+        (Some(dir), Some(name)) => {
+            let container_socket = format!("{}/{}", container_dir, name);
+            debug!(
+                "Forwarding {} socket: host {} -> container {}",
+                what, socket_path, container_socket
+            );
+            let command = command.add_socket_mount(&(dir.into(), container_dir.into()));
+            Ok(match env_var {
+                Some(var) => command.add_environment(&(var.into(), container_socket)),
+                None => command,
+            })
+        }
+        _ => Err(FlokiError::SocketForwardError {
+            what: what.into(),
+            msg: format!("could not parse socket path: {}", socket_path),
+        })?,
+    }
+}
+
 pub fn enable_forward_ssh_agent(command: DockerCommandBuilder) -> Result<DockerCommandBuilder> {
     let agent_socket = env::var("SSH_AUTH_SOCK")?;
     debug!("Got SSH_AUTH_SOCK={}", agent_socket);
-    if let Some(dir) = path::Path::new(&agent_socket)
+    let dir = path::Path::new(&agent_socket)
         .parent()
         .and_then(|p| p.to_str())
-    {
-        Ok(command
-            .add_environment(&("SSH_AUTH_SOCK".into(), agent_socket.clone()))
-            .add_volume(&(dir.into(), dir.into())))
-    } else {
-        Err(FlokiError::NoSshAuthSock {})?
-    }
+        .ok_or(FlokiError::NoSshAuthSock {})?;
+    forward_host_socket(
+        command,
+        &agent_socket,
+        dir,
+        Some("SSH_AUTH_SOCK"),
+        "ssh-agent",
+    )
 }
 
 pub fn enable_forward_tmux_socket(command: DockerCommandBuilder) -> Result<DockerCommandBuilder> {
@@ -117,39 +502,532 @@ pub fn enable_forward_tmux_socket(command: DockerCommandBuilder) -> Result<Docke
     debug!("Got TMUX={}", tmux_env);
     let tmux_params: Vec<&str> = tmux_env.split(',').collect();
     match tmux_params.get(0) {
-        Some(path) => {
-            let tmux_path = path::Path::new(path);
-            if let (Some(dir), Some(name)) = (
-                tmux_path.parent().and_then(|d| d.to_str()),
-                tmux_path.file_name().and_then(|f| f.to_str()),
-            ) {
-                debug!(
-                    "tmux socket directory: {}, tmux socket filename: {}",
-                    dir, name
-                );
-                Ok(command
-                    .add_environment(&("TMUX_SOCKET".into(), String::from("/run/tmux/") + name))
-                    .add_volume(&(dir.into(), "/run/tmux".into())))
-            } else {
-                Err(FlokiError::TmuxForwardError {
-                    msg: "tmux socket in env has bad filename".into(),
-                })?
-            }
-        }
+        Some(path) => forward_host_socket(command, path, "/run/tmux", Some("TMUX_SOCKET"), "tmux"),
         None => Err(FlokiError::TmuxForwardError {
             msg: "Could not get tmux socket from environment".into(),
         })?,
     }
 }
 
+/// Falls back to `$GNUPGHOME/S.gpg-agent` if `gpgconf` isn't available.
+fn locate_gpg_agent_socket() -> Result<String> {
+    let output = Command::new("gpgconf").args(&["--list-dir", "agent-socket"]).output();
+
+    if let Ok(output) = output {
+        if output.status.success() {
+            let socket = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            if !socket.is_empty() {
+                return Ok(socket);
+            }
+        }
+    }
+
+    let gnupghome = env::var("GNUPGHOME")?;
+    Ok(format!("{}/S.gpg-agent", gnupghome))
+}
+
+/// Falls back to `$GNUPGHOME`, then `~/.gnupg`, if `gpgconf` isn't available.
+fn locate_gpg_homedir() -> Result<String> {
+    let output = Command::new("gpgconf").args(&["--list-dir", "homedir"]).output();
+
+    if let Ok(output) = output {
+        if output.status.success() {
+            let homedir = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            if !homedir.is_empty() {
+                return Ok(homedir);
+            }
+        }
+    }
+
+    if let Ok(gnupghome) = env::var("GNUPGHOME") {
+        return Ok(gnupghome);
+    }
+
+    let home = env::var("HOME")?;
+    Ok(format!("{}/.gnupg", home))
+}
+
+pub fn enable_forward_gpg_agent(command: DockerCommandBuilder) -> Result<DockerCommandBuilder> {
+    let agent_socket = locate_gpg_agent_socket()?;
+    debug!("Got gpg-agent socket={}", agent_socket);
+    let socket_dir = path::Path::new(&agent_socket)
+        .parent()
+        .and_then(|p| p.to_str())
+        .ok_or_else(|| FlokiError::SocketForwardError {
+            what: "gpg-agent".into(),
+            msg: format!("could not determine parent directory of {}", agent_socket),
+        })?;
+    let command = forward_host_socket(command, &agent_socket, socket_dir, None, "gpg-agent")?;
+
+    // The socket dir alone has no keyring, so `gpg --list-keys`/signing
+    // would still come up empty inside the container; bind-mount the
+    // real homedir too and point GNUPGHOME at it instead of the socket
+    // dir. This assumes the container can write under that same
+    // absolute path (true for the common same-UID case floki targets).
+    let homedir = locate_gpg_homedir()?;
+    let command = command.add_socket_mount(&(homedir.clone(), homedir.clone()));
+    Ok(command.add_environment(&("GNUPGHOME".into(), homedir)))
+}
+
 pub fn enable_docker_in_docker(
     command: DockerCommandBuilder,
     dind: &mut dind::Dind,
 ) -> Result<DockerCommandBuilder> {
-    debug!("docker-in-docker: {:?}", &dind);
-    dind::dind_preflight()?;
-    dind.launch()?;
-    Ok(command
-        .add_docker_switch(&format!("--link {}:floki-docker", dind.name))
-        .add_environment(&("DOCKER_HOST".into(), "tcp://floki-docker:2375".into())))
+    if command.engine.is_podman() {
+        // Podman is daemonless, so there's no `floki-docker` sidecar to
+        // `--link` against and no TCP endpoint to hand the inner engine.
+        // Rather than silently doing nothing, fail loudly until rootless
+        // nested podman is supported.
+        Err(FlokiError::UnsupportedEngineFeature {
+            engine: format!("{:?}", command.engine),
+            feature: "docker-in-docker".into(),
+        })?
+    } else {
+        debug!("docker-in-docker: {:?}", &dind);
+        dind::dind_preflight()?;
+        dind.launch()?;
+        Ok(command
+            .add_docker_switch(&format!("--link {}:floki-docker", dind.name))
+            .add_environment(&("DOCKER_HOST".into(), "tcp://floki-docker:2375".into())))
+    }
+}
+
+/// Tags every data volume floki creates, for listing/pruning later.
+const FLOKI_VOLUME_LABEL: &str = "floki.managed-volume";
+
+fn is_remote_docker_host() -> bool {
+    match env::var("DOCKER_HOST") {
+        Ok(host) => host.starts_with("tcp://") || host.starts_with("ssh://"),
+        Err(_) => false,
+    }
+}
+
+pub fn should_use_remote_volumes() -> bool {
+    env::var("FLOKI_REMOTE").is_ok() || is_remote_docker_host()
+}
+
+/// Dropping this removes the volume, including on early `?` returns.
+#[derive(Debug)]
+pub struct DataVolumeGuard {
+    pub name: String,
+    engine: Engine,
+}
+
+impl DataVolumeGuard {
+    pub fn create(engine: &Engine, name: &str) -> Result<Self> {
+        debug!("Creating remote data volume {}", name);
+        let status = Command::new(engine.binary())
+            .args(&["volume", "create", "--label", FLOKI_VOLUME_LABEL])
+            .arg(name)
+            .status()
+            .map_err(|e| FlokiError::FailedToLaunchDocker { error: e })?;
+
+        if !status.success() {
+            Err(FlokiError::FailedToCreateDataVolume { name: name.into() })?
+        }
+
+        Ok(DataVolumeGuard {
+            name: name.into(),
+            engine: engine.clone(),
+        })
+    }
+
+    pub fn populate(&self, source_dir: &str) -> Result<()> {
+        debug!("Populating {} from {}", self.name, source_dir);
+        let mut tar = Command::new("tar")
+            .args(&["-C", source_dir, "-cf", "-", "."])
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| FlokiError::FailedToLaunchDocker { error: e })?;
+        let tar_stdout = tar
+            .stdout
+            .take()
+            .ok_or_else(|| FlokiError::FailedToCreateDataVolume {
+                name: self.name.clone(),
+            })?;
+
+        let status = Command::new(self.engine.binary())
+            .args(&["run", "--rm", "-i"])
+            .arg("-v")
+            .arg(format!("{}:/data", self.name))
+            .arg("busybox")
+            .args(&["tar", "-C", "/data", "-xf", "-"])
+            .stdin(tar_stdout)
+            .status()
+            .map_err(|e| FlokiError::FailedToLaunchDocker { error: e })?;
+
+        // The helper container can exit 0 on a truncated/empty archive
+        // if `tar` itself failed or was killed, so both exit statuses
+        // must be checked -- not just the consumer's.
+        let tar_status = tar
+            .wait()
+            .map_err(|e| FlokiError::FailedToCompleteDockerCommand { error: e })?;
+
+        if !tar_status.success() || !status.success() {
+            Err(FlokiError::FailedToCreateDataVolume {
+                name: self.name.clone(),
+            })?
+        }
+        Ok(())
+    }
+
+    pub fn copy_back(&self, dest_dir: &str) -> Result<()> {
+        debug!("Copying {} back to {}", self.name, dest_dir);
+        let mut container = Command::new(self.engine.binary())
+            .args(&["run", "--rm", "-i"])
+            .arg("-v")
+            .arg(format!("{}:/data", self.name))
+            .arg("busybox")
+            .args(&["tar", "-C", "/data", "-cf", "-", "."])
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| FlokiError::FailedToLaunchDocker { error: e })?;
+        let container_stdout =
+            container
+                .stdout
+                .take()
+                .ok_or_else(|| FlokiError::FailedToCreateDataVolume {
+                    name: self.name.clone(),
+                })?;
+
+        let tar_status = Command::new("tar")
+            .args(&["-C", dest_dir, "-xf", "-"])
+            .stdin(container_stdout)
+            .status()
+            .map_err(|e| FlokiError::FailedToLaunchDocker { error: e })?;
+
+        // Same reasoning as `populate`: both ends of the pipe can report
+        // success independently of each other, so both must be checked.
+        let container_status = container
+            .wait()
+            .map_err(|e| FlokiError::FailedToCompleteDockerCommand { error: e })?;
+
+        if !container_status.success() || !tar_status.success() {
+            Err(FlokiError::FailedToCreateDataVolume {
+                name: self.name.clone(),
+            })?
+        }
+        Ok(())
+    }
+}
+
+impl Drop for DataVolumeGuard {
+    fn drop(&mut self) {
+        debug!("Removing remote data volume {}", self.name);
+        if let Err(e) = Command::new(self.engine.binary())
+            .args(&["volume", "rm", "-f"])
+            .arg(&self.name)
+            .status()
+        {
+            warn!("Failed to remove floki data volume {}: {}", self.name, e);
+        }
+    }
+}
+
+pub fn list_floki_volumes(engine: &Engine) -> Result<Vec<String>> {
+    let output = Command::new(engine.binary())
+        .args(&["volume", "ls", "-q", "--filter"])
+        .arg(format!("label={}", FLOKI_VOLUME_LABEL))
+        .output()
+        .map_err(|e| FlokiError::FailedToLaunchDocker { error: e })?;
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(String::from)
+        .collect())
+}
+
+pub fn prune_floki_volumes(engine: &Engine) -> Result<()> {
+    for name in list_floki_volumes(engine)? {
+        debug!("Pruning floki data volume {}", name);
+        Command::new(engine.binary())
+            .args(&["volume", "rm", "-f"])
+            .arg(&name)
+            .status()
+            .map_err(|e| FlokiError::FailedToLaunchDocker { error: e })?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub enum VolumesCommand {
+    #[structopt(name = "list")]
+    List,
+    #[structopt(name = "prune")]
+    Prune,
+}
+
+pub fn run_volumes_command(engine: &Engine, command: &VolumesCommand) -> Result<()> {
+    match command {
+        VolumesCommand::List => {
+            for name in list_floki_volumes(engine)? {
+                println!("{}", name);
+            }
+            Ok(())
+        }
+        VolumesCommand::Prune => prune_floki_volumes(engine),
+    }
+}
+
+/// Docker Engine API backend, gated behind the `docker-api` feature.
+#[cfg(feature = "docker-api")]
+mod api {
+    use super::{DockerCommandBuilder, FlokiError, PullPolicy};
+    use futures::{Future, Stream};
+    use quicli::prelude::*;
+    use shiplift::tty::TtyChunk;
+    use shiplift::{AttachContainerOptions, ContainerOptions, Docker};
+    use std::io::{self, Write};
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+    use tokio::runtime::current_thread::Runtime;
+
+    /// Errors loudly instead of silently launching an unconfigured
+    /// container when ports/pull_policy/security were set.
+    fn reject_unsupported_options(builder: &DockerCommandBuilder) -> Result<()> {
+        let mut unsupported = Vec::new();
+        if !builder.ports.is_empty() {
+            unsupported.push("ports");
+        }
+        if builder.pull_policy != PullPolicy::Missing || !builder.use_pull_flag {
+            unsupported.push("pull_policy");
+        }
+        if builder.security.seccomp
+            || !builder.security.cap_add.is_empty()
+            || !builder.security.cap_drop.is_empty()
+            || builder.security.read_only
+        {
+            unsupported.push("security");
+        }
+        if !builder.switches.is_empty() {
+            unsupported.push("switches");
+        }
+
+        if unsupported.is_empty() {
+            Ok(())
+        } else {
+            Err(FlokiError::UnsupportedEngineFeature {
+                engine: format!("{:?}", builder.engine),
+                feature: format!(
+                    "docker-api backend does not yet forward: {}",
+                    unsupported.join(", ")
+                ),
+            })?
+        }
+    }
+
+    pub fn run_via_api(
+        builder: &DockerCommandBuilder,
+        subshell_command: String,
+    ) -> Result<ExitStatus> {
+        debug!(
+            "Launching container via Docker Engine API, configuration: {:?} args: {}",
+            builder, &subshell_command
+        );
+
+        reject_unsupported_options(builder)?;
+
+        let docker = Docker::new();
+        let mut runtime = Runtime::new().map_err(|e| FlokiError::FailedToLaunchDocker {
+            error: e,
+        })?;
+
+        let mut opts = ContainerOptions::builder(&builder.image);
+        opts.cmd(vec![
+            builder.shell.as_str(),
+            "-c",
+            subshell_command.as_str(),
+        ]);
+        opts.volumes(
+            builder
+                .volumes
+                .iter()
+                .chain(builder.socket_mounts.iter())
+                .map(|(s, d)| format!("{}:{}", s, d))
+                .collect::<Vec<_>>()
+                .iter()
+                .map(String::as_str)
+                .collect(),
+        );
+        opts.env(
+            builder
+                .environment
+                .iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect::<Vec<_>>(),
+        );
+
+        let container = runtime
+            .block_on(docker.containers().create(&opts.build()))
+            .map_err(|e| FlokiError::ApiRunFailed {
+                msg: format!("failed to create container: {}", e),
+            })?;
+
+        let containers = docker.containers();
+        let handle = containers.get(&container.id);
+
+        runtime
+            .block_on(handle.start())
+            .map_err(|e| FlokiError::ApiRunFailed {
+                msg: format!("failed to start container: {}", e),
+            })?;
+
+        // Attach with stdin *and* stdout/stderr open, and split the
+        // resulting duplex stream: pump our own stdin into the write
+        // half on its own task while we read the multiplexed output on
+        // this one. Without this, the API backend can only launch
+        // non-interactive commands -- no good for floki's subshell.
+        let attach_opts = AttachContainerOptions::builder()
+            .stdin(true)
+            .stdout(true)
+            .stderr(true)
+            .stream(true)
+            .build();
+
+        let attached = runtime
+            .block_on(handle.attach(&attach_opts))
+            .map_err(|e| FlokiError::ApiRunFailed {
+                msg: format!("failed to attach to container: {}", e),
+            })?;
+        let (container_stdin, container_output) = attached.split();
+
+        runtime.spawn(
+            tokio::io::copy(tokio::io::stdin(), container_stdin)
+                .map(|_| ())
+                .map_err(|e| warn!("error forwarding stdin to container: {}", e)),
+        );
+
+        runtime
+            .block_on(container_output.for_each(|chunk| {
+                match chunk {
+                    TtyChunk::StdOut(bytes) => io::stdout().write_all(&bytes),
+                    TtyChunk::StdErr(bytes) => io::stderr().write_all(&bytes),
+                    TtyChunk::StdIn(_) => Ok(()),
+                }
+            }))
+            .map_err(|e| FlokiError::ApiRunFailed {
+                msg: format!("error reading container output: {}", e),
+            })?;
+
+        let exit = runtime
+            .block_on(handle.wait())
+            .map_err(|e| FlokiError::ApiRunFailed {
+                msg: format!("failed to wait for container exit: {}", e),
+            })?;
+
+        // `from_raw` expects a wait(2)-encoded status (exit code in bits
+        // 8-15), not a bare exit code -- without the shift, e.g. exit
+        // code 1 decodes as "killed by signal 1" instead of exit(1).
+        Ok(ExitStatus::from_raw((exit.status_code as i32) << 8))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn security_switches_cover_seccomp_caps_and_read_only() {
+        let builder = DockerCommandBuilder::new("image", "/bin/sh").with_security_options(
+            SecurityOptions {
+                seccomp: true,
+                cap_add: vec!["SYS_PTRACE".into()],
+                cap_drop: vec!["NET_RAW".into()],
+                read_only: true,
+            },
+        );
+        let guard = SeccompProfileGuard::write().unwrap();
+        let switches = builder.build_security_switches(Some(&guard));
+
+        assert_eq!(
+            switches,
+            vec![
+                "--security-opt".to_string(),
+                format!("seccomp={}", guard.path.display()),
+                "--cap-add".into(),
+                "SYS_PTRACE".into(),
+                "--cap-drop".into(),
+                "NET_RAW".into(),
+                "--read-only".into(),
+            ]
+        );
+    }
+
+    #[test]
+    fn security_switches_empty_by_default() {
+        let builder = DockerCommandBuilder::new("image", "/bin/sh");
+        assert!(builder.build_security_switches(None).is_empty());
+    }
+
+    #[test]
+    fn port_switches_chain_after_other_builder_calls() {
+        let builder = DockerCommandBuilder::new("image", "/bin/sh")
+            .add_volume(&("/host".into(), "/container".into()))
+            .add_port((8080, 80))
+            .add_port((0, 443));
+
+        assert_eq!(
+            builder.build_port_switches(),
+            vec!["-p", "8080:80", "-p", "0:443"]
+        );
+    }
+
+    #[test]
+    fn engine_from_config_or_env_prefers_config_over_env() {
+        // These mutate a process-global env var; run `cargo test -- --test-threads=1`
+        // if this ever flakes alongside another test touching FLOKI_CONTAINER_ENGINE.
+        let prior = env::var("FLOKI_CONTAINER_ENGINE").ok();
+
+        env::remove_var("FLOKI_CONTAINER_ENGINE");
+        assert_eq!(Engine::from_config_or_env(None), Engine::Docker);
+        assert_eq!(Engine::from_config_or_env(Some("podman")), Engine::Podman);
+
+        env::set_var("FLOKI_CONTAINER_ENGINE", "podman");
+        assert_eq!(Engine::from_config_or_env(None), Engine::Podman);
+        assert_eq!(Engine::from_config_or_env(Some("docker")), Engine::Docker);
+
+        env::set_var("FLOKI_CONTAINER_ENGINE", "/usr/local/bin/my-podman");
+        assert_eq!(
+            Engine::from_config_or_env(None),
+            Engine::Path("/usr/local/bin/my-podman".into())
+        );
+
+        match prior {
+            Some(v) => env::set_var("FLOKI_CONTAINER_ENGINE", v),
+            None => env::remove_var("FLOKI_CONTAINER_ENGINE"),
+        }
+    }
+
+    #[test]
+    fn pull_switch_reflects_policy_only_when_supported() {
+        let builder = DockerCommandBuilder::new("image", "/bin/sh").with_pull_policy(PullPolicy::Always);
+        assert_eq!(builder.build_pull_switch(), vec!["--pull=always"]);
+
+        let builder = builder.with_pull_flag_support(false);
+        assert!(builder.build_pull_switch().is_empty());
+    }
+
+    #[test]
+    fn remote_docker_host_detects_tcp_and_ssh() {
+        // These mutate a process-global env var; run `cargo test -- --test-threads=1`
+        // if this ever flakes alongside another test touching DOCKER_HOST.
+        let prior = env::var("DOCKER_HOST").ok();
+
+        env::set_var("DOCKER_HOST", "tcp://example.com:2375");
+        assert!(is_remote_docker_host());
+
+        env::set_var("DOCKER_HOST", "ssh://example.com");
+        assert!(is_remote_docker_host());
+
+        env::set_var("DOCKER_HOST", "unix:///var/run/docker.sock");
+        assert!(!is_remote_docker_host());
+
+        env::remove_var("DOCKER_HOST");
+        assert!(!is_remote_docker_host());
+
+        match prior {
+            Some(v) => env::set_var("DOCKER_HOST", v),
+            None => env::remove_var("DOCKER_HOST"),
+        }
+    }
 }
\ No newline at end of file